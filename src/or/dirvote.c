@@ -1220,10 +1220,11 @@ get_nth_protocol_set_vote(int n, const networkstatus_t *vote)
 
 /** Considering the different recommended/required protocols sets as a
  * 4-element array, return a newly allocated string for the consensus value
- * for the n'th set.
+ * for the n'th set, using <b>consensus_method</b>.
  */
 static char *
-compute_nth_protocol_set(int n, int n_voters, const smartlist_t *votes)
+compute_nth_protocol_set(int n, int n_voters, const smartlist_t *votes,
+                         int consensus_method)
 {
   const char *keyword;
   smartlist_t *proto_votes = smartlist_new();
@@ -1256,7 +1257,8 @@ compute_nth_protocol_set(int n, int n_voters, const smartlist_t *votes)
       smartlist_add(proto_votes, (void*)v);
   } SMARTLIST_FOREACH_END(ns);
 
-  char *protocols = protover_compute_vote(proto_votes, threshold);
+  char *protocols = protover_compute_vote(proto_votes, threshold,
+                                         consensus_method);
   smartlist_free(proto_votes);
 
   char *result = NULL;
@@ -1449,7 +1451,8 @@ networkstatus_compute_consensus(smartlist_t *votes,
     int num_dirauth = get_n_authorities(V3_DIRINFO);
     int idx;
     for (idx = 0; idx < 4; ++idx) {
-      char *proto_line = compute_nth_protocol_set(idx, num_dirauth, votes);
+      char *proto_line = compute_nth_protocol_set(idx, num_dirauth, votes,
+                                                  consensus_method);
       if (BUG(!proto_line))
         continue;
       smartlist_add(chunks, proto_line);
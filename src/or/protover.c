@@ -23,6 +23,8 @@
 #define PROTOVER_PRIVATE
 
 #include "or.h"
+#include "config.h"
+#include "dirvote.h"
 #include "protover.h"
 #include "routerparse.h"
 
@@ -106,11 +108,16 @@ proto_entry_free(proto_entry_t *entry)
  * Given a string <b>s</b> and optional end-of-string pointer
  * <b>end_of_range</b>, parse the protocol range and store it in
  * <b>low_out</b> and <b>high_out</b>.  A protocol range has the format U, or
- * U-U, where U is an unsigned 32-bit integer.
+ * U-U, where U is an unsigned 32-bit integer. A range whose bounds are
+ * equal, like 2-2, is valid, and means the same as the single version.
+ * Version 0 parses like any other version.
+ *
+ * If <b>strict</b> is false, accept anything tor_parse_ulong() accepts as a
+ * bound, as authorities did before MIN_METHOD_FOR_STRICT_PROTOVER.
  */
 static int
 parse_version_range(const char *s, const char *end_of_range,
-                    uint32_t *low_out, uint32_t *high_out)
+                    uint32_t *low_out, uint32_t *high_out, int strict)
 {
   uint32_t low, high;
   char *next = NULL;
@@ -122,6 +129,11 @@ parse_version_range(const char *s, const char *end_of_range,
   if (BUG(!end_of_range))
     end_of_range = s + strlen(s); // LCOV_EXCL_LINE
 
+  /* A range must start with a digit: tor_parse_ulong() would otherwise let
+   * through whitespace, signs, and other things that aren't versions. */
+  if (strict && !TOR_ISDIGIT(*s))
+    goto error;
+
   /* Note that this wouldn't be safe if we didn't know that eventually,
    * we'd hit a NUL */
   low = (uint32_t) tor_parse_ulong(s, 10, 0, UINT32_MAX, &ok, &next);
@@ -138,6 +150,8 @@ parse_version_range(const char *s, const char *end_of_range,
     goto error;
   s = next+1;
   /* ibid */
+  if (strict && !TOR_ISDIGIT(*s))
+    goto error;
   high = (uint32_t) tor_parse_ulong(s, 10, 0, UINT32_MAX, &ok, &next);
   if (!ok)
     goto error;
@@ -153,13 +167,33 @@ parse_version_range(const char *s, const char *end_of_range,
   return -1;
 }
 
+/** Return true iff the <b>n</b> characters at <b>s</b> form a valid protocol
+ * keyword: that is, they are all letters, digits, or hyphens. */
+static int
+is_valid_keyword(const char *s, size_t n)
+{
+  size_t i;
+  for (i = 0; i < n; ++i) {
+    if (!TOR_ISALNUM(s[i]) && s[i] != '-')
+      return 0;
+  }
+  return 1;
+}
+
 /** Parse a single protocol entry from <b>s</b> up to an optional
  * <b>end_of_entry</b> pointer, and return that protocol entry. Return NULL
  * on error.
  *
- * A protocol entry has a keyword, an = sign, and zero or more ranges. */
+ * A protocol entry has a keyword, an = sign, and zero or more ranges,
+ * separated by commas. Extra commas between or after the ranges are
+ * ignored. (Unlike bad keywords or versions, those have only one possible
+ * reading, and re-encode canonically, so there's no need to start rejecting
+ * them and to gate that on a consensus method.)
+ *
+ * If <b>strict</b> is false, parse the way authorities did before
+ * MIN_METHOD_FOR_STRICT_PROTOVER. */
 static proto_entry_t *
-parse_single_entry(const char *s, const char *end_of_entry)
+parse_single_entry(const char *s, const char *end_of_entry, int strict)
 {
   proto_entry_t *out = tor_malloc_zero(sizeof(proto_entry_t));
   const char *equals;
@@ -178,6 +212,11 @@ parse_single_entry(const char *s, const char *end_of_entry)
   if (equals == s)
     goto error;
 
+  /* The name must not contain separators or other junk that would make it
+   * impossible to re-encode. */
+  if (strict && !is_valid_keyword(s, equals-s))
+    goto error;
+
   out->name = tor_strndup(s, equals-s);
 
   tor_assert(equals < end_of_entry);
@@ -190,7 +229,8 @@ parse_single_entry(const char *s, const char *end_of_entry)
       comma = end_of_entry;
 
     smartlist_add(out->ranges, range);
-    if (parse_version_range(s, comma, &range->low, &range->high) < 0) {
+    if (parse_version_range(s, comma, &range->low, &range->high,
+                            strict) < 0) {
       goto error;
     }
 
@@ -212,13 +252,24 @@ parse_single_entry(const char *s, const char *end_of_entry)
 
 /**
  * Parse the protocol list from <b>s</b> and return it as a smartlist of
- * proto_entry_t
+ * proto_entry_t.  On failure, return NULL, and if <b>bad_entry_out</b> is
+ * provided, set it to a newly allocated copy of the entry that we couldn't
+ * parse, so that the caller can say what was wrong.  If <b>strict</b> is
+ * false, parse the way authorities did before MIN_METHOD_FOR_STRICT_PROTOVER.
+ *
+ * Entries are separated by ASCII spaces only: tabs and non-ASCII whitespace
+ * (such as a UTF-8 non-breaking space) are not separators, and will make the
+ * entry that contains them fail to parse. Leading, trailing, and repeated
+ * spaces are ignored.
  */
-STATIC smartlist_t *
-parse_protocol_list(const char *s)
+static smartlist_t *
+parse_protocol_list_impl(const char *s, int strict, char **bad_entry_out)
 {
   smartlist_t *entries = smartlist_new();
 
+  while (*s == ' ')
+    ++s;
+
   while (*s) {
     /* Find the next space or the NUL. */
     const char *end_of_entry = strchr(s, ' ');
@@ -226,10 +277,13 @@ parse_protocol_list(const char *s)
     if (!end_of_entry)
       end_of_entry = s + strlen(s);
 
-    entry = parse_single_entry(s, end_of_entry);
+    entry = parse_single_entry(s, end_of_entry, strict);
 
-    if (! entry)
+    if (! entry) {
+      if (bad_entry_out)
+        *bad_entry_out = tor_strndup(s, end_of_entry - s);
       goto error;
+    }
 
     smartlist_add(entries, entry);
 
@@ -246,6 +300,16 @@ parse_protocol_list(const char *s)
   return NULL;
 }
 
+/**
+ * Parse the protocol list from <b>s</b> and return it as a smartlist of
+ * proto_entry_t, or NULL if it can't be parsed.
+ */
+STATIC smartlist_t *
+parse_protocol_list(const char *s)
+{
+  return parse_protocol_list_impl(s, 1, NULL);
+}
+
 /**
  * Given a protocol type and version number, return true iff we know
  * how to speak that protocol.
@@ -268,8 +332,12 @@ protocol_list_supports_protocol(const char *list, protocol_type_t tp,
   /* NOTE: This is a pretty inefficient implementation. If it ever shows
    * up in profiles, we should memoize it.
    */
-  smartlist_t *protocols = parse_protocol_list(list);
+  char *bad_entry = NULL;
+  smartlist_t *protocols = parse_protocol_list_impl(list, 1, &bad_entry);
   if (!protocols) {
+    log_fn(LOG_PROTOCOL_WARN, LD_DIR, "Couldn't parse protocol entry %s",
+           escaped(bad_entry));
+    tor_free(bad_entry);
     return 0;
   }
   int contains = protocol_list_contains(protocols, tp, version);
@@ -279,10 +347,50 @@ protocol_list_supports_protocol(const char *list, protocol_type_t tp,
   return contains;
 }
 
+/**
+ * Return true iff "list" encodes a protocol list that includes support for
+ * the indicated protocol and version, or some later version.
+ */
+int
+protocol_list_supports_protocol_or_later(const char *list,
+                                         protocol_type_t tp,
+                                         uint32_t version)
+{
+  /* NOTE: This is a pretty inefficient implementation. If it ever shows
+   * up in profiles, we should memoize it.
+   */
+  char *bad_entry = NULL;
+  smartlist_t *protocols = parse_protocol_list_impl(list, 1, &bad_entry);
+  if (!protocols) {
+    log_fn(LOG_PROTOCOL_WARN, LD_DIR, "Couldn't parse protocol entry %s",
+           escaped(bad_entry));
+    tor_free(bad_entry);
+    return 0;
+  }
+  const char *pr_name = protocol_type_to_str(tp);
+
+  int contains = 0;
+  SMARTLIST_FOREACH_BEGIN(protocols, proto_entry_t *, proto) {
+    if (strcasecmp(proto->name, pr_name))
+      continue;
+    SMARTLIST_FOREACH_BEGIN(proto->ranges, const proto_range_t *, range) {
+      if (range->high >= version) {
+        contains = 1;
+        goto found;
+      }
+    } SMARTLIST_FOREACH_END(range);
+  } SMARTLIST_FOREACH_END(proto);
+
+ found:
+  SMARTLIST_FOREACH(protocols, proto_entry_t *, ent, proto_entry_free(ent));
+  smartlist_free(protocols);
+  return contains;
+}
+
 /** Return the canonical string containing the list of protocols
  * that we support. */
-const char *
-protover_get_supported_protocols(void)
+MOCK_IMPL(const char *,
+protover_get_supported_protocols,(void))
 {
   return
     "Cons=1-2 "
@@ -373,10 +481,12 @@ static const int MAX_PROTOCOLS_TO_EXPAND = (1<<16);
  *
  * Do not list any protocol version more than once.
  *
- * Return NULL if the list would be too big.
+ * Return NULL if the list would be too big.  If <b>strict</b> is false,
+ * also return NULL for any range that ends at UINT32_MAX, as authorities did
+ * before MIN_METHOD_FOR_STRICT_PROTOVER.
  */
 static smartlist_t *
-expand_protocol_list(const smartlist_t *protos)
+expand_protocol_list(const smartlist_t *protos, int strict)
 {
   smartlist_t *expanded = smartlist_new();
   if (!protos)
@@ -386,10 +496,21 @@ expand_protocol_list(const smartlist_t *protos)
     const char *name = ent->name;
     SMARTLIST_FOREACH_BEGIN(ent->ranges, const proto_range_t *, range) {
       uint32_t u;
+      /* Reject an oversized range before we allocate anything for it. */
+      if (BUG(range->low > range->high) ||
+          range->high - range->low >= (uint32_t)MAX_PROTOCOLS_TO_EXPAND)
+        goto too_many;
+      /* Older authorities let u wrap around to 0 below, and so always
+       * found a range like this to be too big. */
+      if (!strict && range->high == UINT32_MAX)
+        goto too_many;
       for (u = range->low; u <= range->high; ++u) {
         smartlist_add_asprintf(expanded, "%s=%lu", name, (unsigned long)u);
         if (smartlist_len(expanded) > MAX_PROTOCOLS_TO_EXPAND)
           goto too_many;
+        /* Don't let u wrap around to 0 when high is UINT32_MAX. */
+        if (u == range->high)
+          break;
       }
     } SMARTLIST_FOREACH_END(range);
   } SMARTLIST_FOREACH_END(ent);
@@ -452,7 +573,9 @@ contract_protocol_list(const smartlist_t *proto_strings)
   SMARTLIST_FOREACH_BEGIN(proto_strings, const char *, s) {
     if (BUG(!s))
       continue;// LCOV_EXCL_LINE
-    proto_entry_t *ent = parse_single_entry(s, s+strlen(s));
+    /* These came from expand_protocol_list(), so their names may be
+     * anything that a lenient parse let through. */
+    proto_entry_t *ent = parse_single_entry(s, s+strlen(s), 0);
     if (BUG(!ent))
       continue; // LCOV_EXCL_LINE
     smartlist_t *lst = strmap_get(entry_lists_by_name, ent->name);
@@ -543,17 +666,30 @@ contract_protocol_list(const smartlist_t *proto_strings)
  *
  * The string is minimal and sorted according to the rules of
  * contract_protocol_list above.
+ *
+ * Parse the inputs as authorities using <b>consensus_method</b> do, so that
+ * all of them reach the same result.
  */
 char *
 protover_compute_vote(const smartlist_t *list_of_proto_strings,
-                      int threshold)
+                      int threshold, int consensus_method)
 {
   smartlist_t *all_entries = smartlist_new();
+  const int strict = consensus_method >= MIN_METHOD_FOR_STRICT_PROTOVER;
 
   // First, parse the inputs and break them into singleton entries.
   SMARTLIST_FOREACH_BEGIN(list_of_proto_strings, const char *, vote) {
-    smartlist_t *unexpanded = parse_protocol_list(vote);
-    smartlist_t *this_vote = expand_protocol_list(unexpanded);
+    char *bad_entry = NULL;
+    smartlist_t *unexpanded = parse_protocol_list_impl(vote, strict,
+                                                       &bad_entry);
+    if (! unexpanded) {
+      log_warn(LD_NET, "I failed with parsing a protocol list from "
+               "an authority. The offending entry was: %s",
+               escaped(bad_entry));
+      tor_free(bad_entry);
+      continue;
+    }
+    smartlist_t *this_vote = expand_protocol_list(unexpanded, strict);
     if (this_vote == NULL) {
       log_warn(LD_NET, "When expanding a protocol list from an authority, I "
                "got too many protocols. This is possibly an attack or a bug, "
@@ -573,8 +709,10 @@ protover_compute_vote(const smartlist_t *list_of_proto_strings,
 
   // Now find all the strings that appear at least 'threshold' times.
   smartlist_t *include_entries = smartlist_new();
-  const char *cur_entry = smartlist_get(all_entries, 0);
+  const char *cur_entry = NULL;
   int n_times = 0;
+  if (smartlist_len(all_entries))
+    cur_entry = smartlist_get(all_entries, 0);
   SMARTLIST_FOREACH_BEGIN(all_entries, const char *, ent) {
     if (!strcmp(ent, cur_entry)) {
       n_times++;
@@ -602,6 +740,16 @@ protover_compute_vote(const smartlist_t *list_of_proto_strings,
  * one that we support, and false otherwise.  If <b>missing_out</b> is
  * provided, set it to the list of protocols we do not support.
  *
+ * An empty list (or one that is only spaces) asks for nothing, so we
+ * support all of it.
+ *
+ * If <b>s</b> can't be parsed at all, we can't say whether we support it:
+ * log a warning and return true, leaving <b>missing_out</b> unset, so that
+ * a malformed consensus line can't make us decide we're obsolete.
+ *
+ * We support no version 0 of any protocol, so a list that asks for version
+ * 0 of a protocol we know will report that protocol as missing.
+ *
  * NOTE: This is quadratic, but we don't do it much: only a few times per
  * consensus. Checking signatures should be way more expensive than this
  * ever would be.
@@ -616,7 +764,14 @@ protover_all_supported(const char *s, char **missing_out)
     return 1;
   }
 
-  smartlist_t *entries = parse_protocol_list(s);
+  char *bad_entry = NULL;
+  smartlist_t *entries = parse_protocol_list_impl(s, 1, &bad_entry);
+  if (!entries) {
+    log_warn(LD_NET, "Received an unparseable protocol list; the offending "
+             "entry was %s", escaped(bad_entry));
+    tor_free(bad_entry);
+    return 1;
+  }
 
   missing = smartlist_new();
 
@@ -696,7 +851,7 @@ protover_compute_for_old_tor(const char *version)
   if (tor_version_as_new_as(version,
                             FIRST_TOR_VERSION_TO_ADVERTISE_PROTOCOLS)) {
     return "";
-  } else if (tor_version_as_new_as(version, "0.2.7.5")) {
+  } else if (tor_version_as_new_as(version, "0.2.9.1-alpha")) {
     /* 0.2.9.1-alpha HSRend=2 */
     return "Cons=1-2 Desc=1-2 DirCache=1 HSDir=1 HSIntro=3 HSRend=1-2 "
       "Link=1-4 LinkAuth=1 "
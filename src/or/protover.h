@@ -33,13 +33,16 @@ typedef enum protocol_type_t {
 
 int protover_all_supported(const char *s, char **missing);
 int protover_is_supported_here(protocol_type_t pr, uint32_t ver);
-const char *protover_get_supported_protocols(void);
+MOCK_DECL(const char *, protover_get_supported_protocols, (void));
 
 char *protover_compute_vote(const smartlist_t *list_of_proto_strings,
-                            int threshold);
+                            int threshold, int consensus_method);
 const char *protover_compute_for_old_tor(const char *version);
 int protocol_list_supports_protocol(const char *list, protocol_type_t tp,
                                     uint32_t version);
+int protocol_list_supports_protocol_or_later(const char *list,
+                                             protocol_type_t tp,
+                                             uint32_t version);
 
 void protover_free_all(void);
 
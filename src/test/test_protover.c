@@ -6,7 +6,10 @@
 #include "orconfig.h"
 #include "test.h"
 
+#include "or.h"
+#include "dirvote.h"
 #include "protover.h"
+#include "log_test_helpers.h"
 
 static void
 test_protover_parse(void *arg)
@@ -80,6 +83,51 @@ test_protover_parse(void *arg)
   tor_free(re_encoded);
 }
 
+static void
+test_protover_parse_spaces(void *arg)
+{
+  (void) arg;
+  smartlist_t *elts = NULL;
+  char *re_encoded = NULL;
+
+  /* Extra spaces anywhere between entries don't matter. */
+  const char *inputs[] = {
+    "Foo=1,3 Bar=3",
+    " Foo=1,3 Bar=3",
+    "Foo=1,3 Bar=3 ",
+    "Foo=1,3   Bar=3",
+    "   Foo=1,3  Bar=3  ",
+  };
+  unsigned i;
+  for (i = 0; i < ARRAY_LENGTH(inputs); ++i) {
+    elts = parse_protocol_list(inputs[i]);
+    tt_assert(elts);
+    tt_int_op(smartlist_len(elts), OP_EQ, 2);
+    re_encoded = encode_protocol_list(elts);
+    tt_str_op(re_encoded, OP_EQ, "Foo=1,3 Bar=3");
+    tor_free(re_encoded);
+    SMARTLIST_FOREACH(elts, proto_entry_t *, ent, proto_entry_free(ent));
+    smartlist_free(elts);
+    elts = NULL;
+  }
+
+  tt_assert(protover_all_supported("  Link=3-4  Desc=2 ", NULL));
+
+  /* A trailing comma, or a repeated one, is skipped like extra spaces are;
+   * but a list can't start with one. */
+  elts = parse_protocol_list("Foo=1,3, Bar=3,,4");
+  tt_assert(elts);
+  re_encoded = encode_protocol_list(elts);
+  tt_str_op(re_encoded, OP_EQ, "Foo=1,3 Bar=3,4");
+  tt_assert(NULL == parse_protocol_list("Foo=,1"));
+
+ done:
+  if (elts)
+    SMARTLIST_FOREACH(elts, proto_entry_t *, ent, proto_entry_free(ent));
+  smartlist_free(elts);
+  tor_free(re_encoded);
+}
+
 static void
 test_protover_parse_fail(void *arg)
 {
@@ -98,6 +146,18 @@ test_protover_parse_fail(void *arg)
   elts = parse_protocol_list("Link=4 =3 Desc=9");
   tt_assert(elts == NULL);
 
+  /* Bad characters in the word. */
+  elts = parse_protocol_list("Li,nk=4");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link!=4");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Li_nk=4");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Li\x01nk=4");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Li\xc3\xb1k=4");
+  tt_assert(elts == NULL);
+
   /* Broken numbers */
   elts = parse_protocol_list("Link=fred");
   tt_assert(elts == NULL);
@@ -105,45 +165,393 @@ test_protover_parse_fail(void *arg)
   tt_assert(elts == NULL);
   elts = parse_protocol_list("Link=1,fred,3");
   tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=1-fred");
+  tt_assert(elts == NULL);
+
+  /* Signs and whitespace aren't part of a version number. */
+  elts = parse_protocol_list("Link=+3");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=-0");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=1-+3");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=1,\t3");
+  tt_assert(elts == NULL);
 
   /* Broken range */
   elts = parse_protocol_list("Link=1,9-8,3");
   tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=1-2-3");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=-5");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=5-");
+  tt_assert(elts == NULL);
+
+  /* Whitespace can't appear inside an entry */
+  elts = parse_protocol_list("Link =1");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link= 1");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=1 ,2");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=1, 2");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link\t=1");
+  tt_assert(elts == NULL);
+
+  /* Only ASCII spaces separate entries */
+  elts = parse_protocol_list("Link=1\tDesc=9");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=1\xc2\xa0" "Desc=9");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("\tLink=1");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link=1\t");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("\xc2\xa0" "Link=1");
+  tt_assert(elts == NULL);
+  elts = parse_protocol_list("Link\xc2\xa0=1");
+  tt_assert(elts == NULL);
 
  done:
   ;
 }
 
+static void
+test_protover_parse_fail_log(void *arg)
+{
+  (void)arg;
+  smartlist_t *elts;
+
+  /* Parsing on its own is silent: it's up to the caller to complain. */
+  setup_capture_of_logs(LOG_INFO);
+  elts = parse_protocol_list("Link=1-4 Cons=1-x Desc=1");
+  tt_assert(elts == NULL);
+  expect_no_log_entry();
+
+  /* When a list won't parse, we say which entry was the problem. */
+  tt_assert(! protocol_list_supports_protocol("Link=1-4 Cons=1-x Desc=1",
+                                              PRT_LINK, 1));
+  expect_single_log_msg("Couldn't parse protocol entry \"Cons=1-x\"\n");
+  mock_clean_saved_logs();
+
+  /* A stray space splits an entry, and we complain about the half that
+   * can't stand on its own. */
+  tt_assert(! protocol_list_supports_protocol_or_later("Link=1-4 Cons =1",
+                                                       PRT_LINK, 1));
+  expect_single_log_msg("Couldn't parse protocol entry \"Cons\"\n");
+
+ done:
+  teardown_capture_of_logs();
+}
+
 static void
 test_protover_vote(void *arg)
 {
   (void) arg;
 
   smartlist_t *lst = smartlist_new();
-  char *result = protover_compute_vote(lst, 1);
+  char *result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+
+  tt_str_op(result, OP_EQ, "");
+  tor_free(result);
+
+  /* So is a list of votes that don't list anything. */
+  smartlist_add(lst, (void*) "");
+  smartlist_add(lst, (void*) "  ");
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "");
+  tor_free(result);
+  smartlist_clear(lst);
 
+  /* A list whose only vote gets thrown away is just as empty. */
+  smartlist_add(lst, (void*) "Foo=1-4294967295");
+  setup_full_capture_of_logs(LOG_WARN);
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
   tt_str_op(result, OP_EQ, "");
+  expect_single_log_msg_containing("I got too many protocols");
+  teardown_capture_of_logs();
   tor_free(result);
+  smartlist_clear(lst);
 
   smartlist_add(lst, (void*) "Foo=1-10,500 Bar=1,3-7,8");
-  result = protover_compute_vote(lst, 1);
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
   tt_str_op(result, OP_EQ, "Bar=1,3-8 Foo=1-10,500");
   tor_free(result);
 
   smartlist_add(lst, (void*) "Quux=123-456,78 Bar=2-6,8 Foo=9");
-  result = protover_compute_vote(lst, 1);
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
   tt_str_op(result, OP_EQ, "Bar=1-8 Foo=1-10,500 Quux=78,123-456");
   tor_free(result);
 
-  result = protover_compute_vote(lst, 2);
+  result = protover_compute_vote(lst, 2, MAX_SUPPORTED_CONSENSUS_METHOD);
   tt_str_op(result, OP_EQ, "Bar=3-6,8 Foo=9");
   tor_free(result);
 
+  /* A gap of exactly one version is still a gap. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "LinkAuth=1,3");
+  smartlist_add(lst, (void*) "LinkAuth=1,2");
+  result = protover_compute_vote(lst, 2, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "LinkAuth=1");
+  tor_free(result);
+  smartlist_del_keeporder(lst, 1);
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "LinkAuth=1,3");
+  tor_free(result);
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "LinkAuth=1,2");
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "LinkAuth=1-2");
+  tor_free(result);
+
+  /* Ranges that start or stop partway through a list are kept apart. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Foo=1,3 Bar=1,2,4 Baz=1,3,4");
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "Bar=1-2,4 Baz=1,3-4 Foo=1,3");
+  tor_free(result);
+
+  /* An isolated version can trail a range, or lead into one. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Foo=7,1-3 Bar=4-5,1,3 Baz=5");
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "Bar=1,3-5 Baz=5 Foo=1-3,7");
+  tor_free(result);
+
+  /* A range with equal bounds is a single version. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Foo=2-2 Bar=1-1,3");
+  smartlist_add(lst, (void*) "Foo=2");
+  result = protover_compute_vote(lst, 2, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "Foo=2");
+  tor_free(result);
+
+  /* Repeating a version within one vote doesn't count it twice, wherever
+   * in the list the repeat is. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Foo=1,1 Bar=2,1,2 Baz=1-3,2 Quux=1-2,2-3");
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "Bar=1-2 Baz=1-3 Foo=1 Quux=1-3");
+  tor_free(result);
+  result = protover_compute_vote(lst, 2, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "");
+  tor_free(result);
+
+  /* Ranges from different votes that touch or overlap become one range. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Foo=1-2 Bar=1-3 Baz=1,2,3");
+  smartlist_add(lst, (void*) "Foo=3-4 Bar=3-5");
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "Bar=1-5 Baz=1-3 Foo=1-4");
+  tor_free(result);
+
+  /* A vote we can't parse is ignored; it doesn't spoil the others. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Foo=1-3 Bar=9");
+  smartlist_add(lst, (void*) "Foo=2-4 Bar=fred");
+  setup_full_capture_of_logs(LOG_WARN);
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "Bar=9 Foo=1-3");
+  expect_single_log_msg_containing("I failed with parsing a protocol list");
+  teardown_capture_of_logs();
+  tor_free(result);
+
+  /* Ranges at the very top of the version space work, but a range covering
+   * the whole of it is still too wide. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Foo=4294967290-4294967295 Bar=4294967295");
+  smartlist_add(lst, (void*) "Baz=0-4294967295");
+  setup_full_capture_of_logs(LOG_WARN);
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "Bar=4294967295 Foo=4294967290-4294967295");
+  expect_single_log_msg_containing("I got too many protocols");
+  teardown_capture_of_logs();
+  tor_free(result);
+
+  /* An absurdly wide range is rejected without expanding it. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Foo=1-4294967295 Bar=1");
+  smartlist_add(lst, (void*) "Bar=1-4");
+  setup_full_capture_of_logs(LOG_WARN);
+  result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "Bar=1-4");
+  expect_single_log_msg_containing("I got too many protocols");
+  teardown_capture_of_logs();
+  tor_free(result);
+
+ done:
+  teardown_capture_of_logs();
+  tor_free(result);
+  smartlist_free(lst);
+}
+
+static void
+test_protover_vote_strict(void *arg)
+{
+  (void) arg;
+  smartlist_t *lst = smartlist_new();
+  char *result = NULL;
+  const int old_method = MIN_METHOD_FOR_STRICT_PROTOVER - 1;
+
+  /* Authorities using older consensus methods must keep agreeing with each
+   * other, so they still accept what tor_parse_ulong() accepts. */
+  smartlist_add(lst, (void*) "Foo=+3 Bar=1");
+  smartlist_add(lst, (void*) "Bar=1-2");
+  result = protover_compute_vote(lst, 1, old_method);
+  tt_str_op(result, OP_EQ, "Bar=1-2 Foo=3");
+  tor_free(result);
+
+  /* Newer ones throw that vote away. */
+  setup_full_capture_of_logs(LOG_WARN);
+  result = protover_compute_vote(lst, 1, MIN_METHOD_FOR_STRICT_PROTOVER);
+  tt_str_op(result, OP_EQ, "Bar=1-2");
+  expect_single_log_msg_containing("I failed with parsing a protocol list");
+  teardown_capture_of_logs();
+  tor_free(result);
+
+  /* The same goes for names we couldn't re-encode. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Li_nk=4 Bar=1");
+  smartlist_add(lst, (void*) "Bar=1-2");
+  result = protover_compute_vote(lst, 1, old_method);
+  tt_str_op(result, OP_EQ, "Bar=1-2 Li_nk=4");
+  tor_free(result);
+  setup_full_capture_of_logs(LOG_WARN);
+  result = protover_compute_vote(lst, 1, MIN_METHOD_FOR_STRICT_PROTOVER);
+  tt_str_op(result, OP_EQ, "Bar=1-2");
+  expect_single_log_msg_containing("I failed with parsing a protocol list");
+  teardown_capture_of_logs();
+  tor_free(result);
+
+  /* Older authorities reject a range that ends at the top of the version
+   * space as too big. */
+  smartlist_clear(lst);
+  smartlist_add(lst, (void*) "Foo=4294967290-4294967295 Bar=1");
+  smartlist_add(lst, (void*) "Bar=1-2");
+  setup_full_capture_of_logs(LOG_WARN);
+  result = protover_compute_vote(lst, 1, old_method);
+  tt_str_op(result, OP_EQ, "Bar=1-2");
+  expect_single_log_msg_containing("I got too many protocols");
+  teardown_capture_of_logs();
+  tor_free(result);
+  result = protover_compute_vote(lst, 1, MIN_METHOD_FOR_STRICT_PROTOVER);
+  tt_str_op(result, OP_EQ, "Bar=1-2 Foo=4294967290-4294967295");
+  tor_free(result);
+
  done:
+  teardown_capture_of_logs();
   tor_free(result);
   smartlist_free(lst);
 }
 
+static void
+test_protover_vote_order(void *arg)
+{
+  (void) arg;
+  smartlist_t *lst = smartlist_new();
+  char *result = NULL;
+
+  const char *votes[] = {
+    "Link=1-4 Cons=1-2 Wombat=5",
+    "Cons=2 Link=3-5 Desc=1",
+    "Desc=1-2 Wombat=4-5 Link=4",
+    "Link=2,4 Cons=1",
+  };
+  const int n_votes = ARRAY_LENGTH(votes);
+  int perm;
+
+  /* The vote depends only on which inputs there are, not on their order:
+   * try every ordering of the votes and check that the result never
+   * changes. */
+  for (perm = 0; perm < 24; ++perm) {
+    int remaining[4] = { 0, 1, 2, 3 };
+    int n_remaining = n_votes;
+    int code = perm;
+    while (n_remaining) {
+      int idx = code % n_remaining;
+      code /= n_remaining;
+      smartlist_add(lst, (void*) votes[remaining[idx]]);
+      remaining[idx] = remaining[--n_remaining];
+    }
+
+    result = protover_compute_vote(lst, 2, MAX_SUPPORTED_CONSENSUS_METHOD);
+    tt_str_op(result, OP_EQ, "Cons=1-2 Desc=1 Link=2-4 Wombat=5");
+    tor_free(result);
+    smartlist_clear(lst);
+  }
+
+ done:
+  smartlist_free(lst);
+  tor_free(result);
+}
+
+static void
+test_protover_vote_roundtrip(void *arg)
+{
+  (void) arg;
+  smartlist_t *lst = smartlist_new();
+  smartlist_t *chunks = smartlist_new();
+  smartlist_t *parsed = NULL;
+  char *input = NULL, *result = NULL;
+  unsigned bits;
+
+  /* Try every subset of versions 1..12, written as an unordered list of
+   * singletons. Contracting the set and reading it back must give exactly
+   * the same versions. */
+  for (bits = 1; bits < (1u<<12); ++bits) {
+    uint32_t v;
+    for (v = 12; v >= 1; --v) {
+      if (bits & (1u << (v-1)))
+        smartlist_add_asprintf(chunks, "%lu", (unsigned long)v);
+    }
+    char *versions = smartlist_join_strings(chunks, ",", 0, NULL);
+    tor_asprintf(&input, "Foo=%s", versions);
+    tor_free(versions);
+    SMARTLIST_FOREACH(chunks, char *, cp, tor_free(cp));
+    smartlist_clear(chunks);
+
+    smartlist_add(lst, input);
+    result = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+    smartlist_clear(lst);
+
+    parsed = parse_protocol_list(result);
+    tt_assert(parsed);
+    tt_int_op(smartlist_len(parsed), OP_EQ, 1);
+    const proto_entry_t *ent = smartlist_get(parsed, 0);
+    tt_str_op(ent->name, OP_EQ, "Foo");
+
+    unsigned found = 0;
+    uint32_t prev_high = 0;
+    SMARTLIST_FOREACH_BEGIN(ent->ranges, const proto_range_t *, range) {
+      /* Ranges are sorted, and separated by at least one missing version. */
+      if (range_sl_idx > 0)
+        tt_int_op(range->low, OP_GT, prev_high + 1);
+      for (v = range->low; v <= range->high; ++v)
+        found |= 1u << (v-1);
+      prev_high = range->high;
+    } SMARTLIST_FOREACH_END(range);
+    tt_int_op(found, OP_EQ, bits);
+
+    SMARTLIST_FOREACH(parsed, proto_entry_t *, e, proto_entry_free(e));
+    smartlist_free(parsed);
+    parsed = NULL;
+    tor_free(input);
+    tor_free(result);
+  }
+
+ done:
+  if (parsed)
+    SMARTLIST_FOREACH(parsed, proto_entry_t *, e, proto_entry_free(e));
+  smartlist_free(parsed);
+  SMARTLIST_FOREACH(chunks, char *, cp, tor_free(cp));
+  smartlist_free(chunks);
+  smartlist_free(lst);
+  tor_free(input);
+  tor_free(result);
+}
+
 static void
 test_protover_all_supported(void *arg)
 {
@@ -155,6 +563,8 @@ test_protover_all_supported(void *arg)
 
   tt_assert(protover_all_supported("", &msg));
   tt_assert(msg == NULL);
+  tt_assert(protover_all_supported("   ", &msg));
+  tt_assert(msg == NULL);
 
   // Some things that we do support
   tt_assert(protover_all_supported("Link=3-4", &msg));
@@ -169,6 +579,32 @@ test_protover_all_supported(void *arg)
   tt_assert(! protover_all_supported("Link=999", &msg));
   tt_str_op(msg, OP_EQ, "Link=999");
   tor_free(msg);
+  // Unknown keywords may use digits and hyphens
+  tt_assert(! protover_all_supported("Wombat-2=9", &msg));
+  tt_str_op(msg, OP_EQ, "Wombat-2=9");
+  tor_free(msg);
+
+  // Version 0 parses, but nothing supports it
+  tt_assert(! protover_all_supported("Cons=0", &msg));
+  tt_str_op(msg, OP_EQ, "Cons=0");
+  tor_free(msg);
+  tt_assert(! protover_all_supported("Link=0-4", &msg));
+  tt_str_op(msg, OP_EQ, "Link=0-4");
+  tor_free(msg);
+
+  // Malformed input is neither supported nor unsupported: we can't tell,
+  // so we warn about it, and we don't report anything missing.
+  setup_full_capture_of_logs(LOG_WARN);
+  tt_assert(protover_all_supported("Link=fred", &msg));
+  tt_assert(msg == NULL);
+  expect_single_log_msg_containing("Received an unparseable protocol list; "
+                                   "the offending entry was \"Link=fred\"");
+  mock_clean_saved_logs();
+  tt_assert(protover_all_supported("Link=3-4 Wombat", &msg));
+  tt_assert(msg == NULL);
+  expect_single_log_msg_containing("Received an unparseable protocol list; "
+                                   "the offending entry was \"Wombat\"");
+  teardown_capture_of_logs();
 
   // Mix of things we support and things we don't
   tt_assert(! protover_all_supported("Link=3-4 Wombat=9", &msg));
@@ -179,17 +615,268 @@ test_protover_all_supported(void *arg)
   tor_free(msg);
 
  done:
+  teardown_capture_of_logs();
+  tor_free(msg);
+}
+
+static const char *
+mock_get_supported_protocols(void)
+{
+  return "Cons=1 Link=1-2,5";
+}
+
+static void
+test_protover_all_supported_mocked(void *arg)
+{
+  (void)arg;
+  char *msg = NULL;
+
+  /* Check against a fixed table, so that these results don't change
+   * whenever we add support for something new. */
+  MOCK(protover_get_supported_protocols, mock_get_supported_protocols);
+  protover_free_all();
+
+  tt_assert(protover_is_supported_here(PRT_LINK, 5));
+  tt_assert(! protover_is_supported_here(PRT_LINK, 3));
+  tt_assert(! protover_is_supported_here(PRT_DESC, 1));
+
+  tt_assert(protover_all_supported("Cons=1 Link=1,5", &msg));
+  tt_assert(msg == NULL);
+
+  tt_assert(! protover_all_supported("Cons=1-2 Link=2-5 Desc=1", &msg));
+  tt_str_op(msg, OP_EQ, "Cons=1-2 Link=2-5 Desc=1");
+  tor_free(msg);
+
+  tt_assert(! protover_all_supported("Link=5 Desc=1", &msg));
+  tt_str_op(msg, OP_EQ, "Desc=1");
+  tor_free(msg);
+
+ done:
+  UNMOCK(protover_get_supported_protocols);
+  protover_free_all();
   tor_free(msg);
 }
 
+static void
+test_protover_list_supports_protocol(void *arg)
+{
+  (void)arg;
+
+  tt_assert(protocol_list_supports_protocol("Link=1-4 Cons=1", PRT_LINK, 3));
+  tt_assert(protocol_list_supports_protocol("Link=1-4 Cons=1", PRT_CONS, 1));
+  tt_assert(! protocol_list_supports_protocol("Link=1-4 Cons=1",
+                                              PRT_CONS, 2));
+
+  /* A protocol that isn't listed at all is simply not supported. */
+  tt_assert(! protocol_list_supports_protocol("Cons=1", PRT_LINK, 3));
+  tt_assert(! protocol_list_supports_protocol("", PRT_LINK, 3));
+  tt_assert(! protocol_list_supports_protocol("  ", PRT_LINK, 3));
+
+  tt_assert(protocol_list_supports_protocol("Link=2-2", PRT_LINK, 2));
+  tt_assert(! protocol_list_supports_protocol("Link=2-2", PRT_LINK, 3));
+
+  /* Version 0 is just another version number. */
+  tt_assert(protocol_list_supports_protocol("Link=0-4", PRT_LINK, 0));
+  tt_assert(! protocol_list_supports_protocol("Link=1-4", PRT_LINK, 0));
+
+  /* Neither is anything in a list we can't parse. */
+  tt_assert(! protocol_list_supports_protocol("Link=fred", PRT_LINK, 3));
+
+ done:
+  ;
+}
+
+static void
+test_protover_supported_protocols(void *arg)
+{
+  (void)arg;
+
+  const char *supported_protocols = protover_get_supported_protocols();
+  smartlist_t *entries = parse_protocol_list(supported_protocols);
+  tt_assert(entries);
+
+  /* Everything we advertise must be something we say we support... */
+  tt_assert(protover_all_supported(supported_protocols, NULL));
+
+  SMARTLIST_FOREACH_BEGIN(entries, const proto_entry_t *, ent) {
+    protocol_type_t tp;
+    tt_int_op(0, OP_EQ, str_to_protocol_type(ent->name, &tp));
+    tt_int_op(smartlist_len(ent->ranges), OP_GT, 0);
+
+    uint32_t highest = 0;
+    SMARTLIST_FOREACH_BEGIN(ent->ranges, const proto_range_t *, range) {
+      uint32_t v;
+      for (v = range->low; v <= range->high; ++v) {
+        tt_assert(protover_is_supported_here(tp, v));
+      }
+      highest = range->high;
+    } SMARTLIST_FOREACH_END(range);
+
+    /* ... and nothing past the end of what we advertise. */
+    tt_assert(! protover_is_supported_here(tp, highest + 1));
+  } SMARTLIST_FOREACH_END(ent);
+
+ done:
+  if (entries)
+    SMARTLIST_FOREACH(entries, proto_entry_t *, ent, proto_entry_free(ent));
+  smartlist_free(entries);
+}
+
+static void
+test_protover_vote_idempotent(void *arg)
+{
+  (void)arg;
+  smartlist_t *lst = smartlist_new();
+  smartlist_t *reparsed = NULL;
+  char *result = NULL, *re_encoded = NULL, *revoted = NULL;
+
+  smartlist_add(lst, (void*) "Link=1-4 Wombat=9,3,4-5 Cons=2 Zed=7");
+  smartlist_add(lst, (void*) "Cons=1-2 Link=3,1-2 Wombat=3-9 LinkAuth=1");
+  smartlist_add(lst, (void*) "Zed=6-8 Link=4-5 Desc=1");
+  result = protover_compute_vote(lst, 2, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(result, OP_EQ, "Cons=2 Link=1-4 Wombat=3-5,9 Zed=7");
+
+  /* The output of a vote is already canonical: parsing and re-encoding
+   * it, or voting on it alone, must give back exactly the same string. */
+  reparsed = parse_protocol_list(result);
+  tt_assert(reparsed);
+  re_encoded = encode_protocol_list(reparsed);
+  tt_str_op(re_encoded, OP_EQ, result);
+
+  smartlist_clear(lst);
+  smartlist_add(lst, result);
+  revoted = protover_compute_vote(lst, 1, MAX_SUPPORTED_CONSENSUS_METHOD);
+  tt_str_op(revoted, OP_EQ, result);
+
+ done:
+  if (reparsed)
+    SMARTLIST_FOREACH(reparsed, proto_entry_t *, ent, proto_entry_free(ent));
+  smartlist_free(reparsed);
+  smartlist_free(lst);
+  tor_free(result);
+  tor_free(re_encoded);
+  tor_free(revoted);
+}
+
+static void
+test_protover_supports_or_later(void *arg)
+{
+  (void)arg;
+
+  tt_assert(protocol_list_supports_protocol_or_later("Link=1-4 Cons=1",
+                                                     PRT_LINK, 3));
+  tt_assert(protocol_list_supports_protocol_or_later("Link=1-4 Cons=1",
+                                                     PRT_LINK, 4));
+  tt_assert(! protocol_list_supports_protocol_or_later("Link=1-4 Cons=1",
+                                                       PRT_LINK, 5));
+
+  /* Any later version counts, even past a gap. */
+  tt_assert(protocol_list_supports_protocol_or_later("Link=1,7",
+                                                     PRT_LINK, 3));
+  tt_assert(protocol_list_supports_protocol_or_later("Link=7",
+                                                     PRT_LINK, 1));
+
+  /* Missing or unparseable protocols are never supported. */
+  tt_assert(! protocol_list_supports_protocol_or_later("Cons=1",
+                                                       PRT_LINK, 1));
+  tt_assert(! protocol_list_supports_protocol_or_later("",
+                                                       PRT_LINK, 1));
+  tt_assert(! protocol_list_supports_protocol_or_later("Link=fred",
+                                                       PRT_LINK, 1));
+
+ done:
+  ;
+}
+
+static void
+test_protover_compute_for_old_tor(void *arg)
+{
+  (void)arg;
+
+  /* Versions that advertise their own protocols. */
+  tt_str_op(protover_compute_for_old_tor("Tor 0.2.9.3-alpha"), OP_EQ, "");
+  tt_str_op(protover_compute_for_old_tor("Tor 0.3.0.1-alpha"), OP_EQ, "");
+
+  tt_str_op(protover_compute_for_old_tor("Tor 0.2.9.1-alpha"), OP_EQ,
+            "Cons=1-2 Desc=1-2 DirCache=1 HSDir=1 HSIntro=3 HSRend=1-2 "
+            "Link=1-4 LinkAuth=1 Microdesc=1-2 Relay=1-2");
+
+  /* 0.2.8 doesn't have HSRend=2. */
+  tt_str_op(protover_compute_for_old_tor("Tor 0.2.8.9"), OP_EQ,
+            "Cons=1-2 Desc=1-2 DirCache=1 HSDir=1 HSIntro=3 HSRend=1 "
+            "Link=1-4 LinkAuth=1 Microdesc=1-2 Relay=1-2");
+  tt_str_op(protover_compute_for_old_tor("Tor 0.2.7.5"), OP_EQ,
+            "Cons=1-2 Desc=1-2 DirCache=1 HSDir=1 HSIntro=3 HSRend=1 "
+            "Link=1-4 LinkAuth=1 Microdesc=1-2 Relay=1-2");
+
+  tt_str_op(protover_compute_for_old_tor("Tor 0.2.5.12"), OP_EQ,
+            "Cons=1 Desc=1 DirCache=1 HSDir=1 HSIntro=3 HSRend=1 "
+            "Link=1-4 LinkAuth=1 Microdesc=1 Relay=1-2");
+
+  /* Too old to guess. */
+  tt_str_op(protover_compute_for_old_tor("Tor 0.2.4.18-rc"), OP_EQ, "");
+
+ done:
+  ;
+}
+
+static void
+test_protover_type_names(void *arg)
+{
+  (void)arg;
+
+  static const struct {
+    protocol_type_t tp;
+    const char *name;
+  } expected[] = {
+    { PRT_LINK, "Link" },
+    { PRT_LINKAUTH, "LinkAuth" },
+    { PRT_RELAY, "Relay" },
+    { PRT_DIRCACHE, "DirCache" },
+    { PRT_HSDIR, "HSDir" },
+    { PRT_HSINTRO, "HSIntro" },
+    { PRT_HSREND, "HSRend" },
+    { PRT_DESC, "Desc" },
+    { PRT_MICRODESC, "Microdesc" },
+    { PRT_CONS, "Cons" },
+  };
+  unsigned i;
+
+  for (i = 0; i < ARRAY_LENGTH(expected); ++i) {
+    protocol_type_t tp;
+    tt_str_op(protocol_type_to_str(expected[i].tp), OP_EQ, expected[i].name);
+    tt_int_op(0, OP_EQ, str_to_protocol_type(expected[i].name, &tp));
+    tt_int_op(tp, OP_EQ, expected[i].tp);
+  }
+
+  protocol_type_t tp;
+  tt_int_op(-1, OP_EQ, str_to_protocol_type("Wombat", &tp));
+  tt_int_op(-1, OP_EQ, str_to_protocol_type("", &tp));
+
+ done:
+  ;
+}
+
 #define PV_TEST(name, flags)                       \
   { #name, test_protover_ ##name, (flags), NULL, NULL }
 
 struct testcase_t protover_tests[] = {
+  PV_TEST(type_names, 0),
   PV_TEST(parse, 0),
+  PV_TEST(parse_spaces, 0),
   PV_TEST(parse_fail, 0),
+  PV_TEST(parse_fail_log, 0),
   PV_TEST(vote, 0),
+  PV_TEST(vote_strict, 0),
+  PV_TEST(vote_idempotent, 0),
+  PV_TEST(vote_order, 0),
+  PV_TEST(vote_roundtrip, 0),
   PV_TEST(all_supported, 0),
+  PV_TEST(all_supported_mocked, 0),
+  PV_TEST(list_supports_protocol, 0),
+  PV_TEST(supports_or_later, 0),
+  PV_TEST(compute_for_old_tor, 0),
+  PV_TEST(supported_protocols, 0),
   END_OF_TESTCASES
 };
 